@@ -0,0 +1,33 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use aptos_metrics_core::{
+    register_histogram_vec, register_int_counter, HistogramVec, IntCounter,
+};
+use once_cell::sync::Lazy;
+
+/// Latency of API responses, labeled by request method, the matched route
+/// template, and the response status code. The `endpoint` label is the
+/// templated path (e.g. `/accounts/:address/resources`) rather than the raw
+/// URI, so embedded addresses, versions, and hashes do not explode the series
+/// cardinality; see [`crate::poem_backend::log`].
+pub static RESPONSE_STATUS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "aptos_api_response_status",
+        "API response latency by method, matched endpoint template, and status code",
+        &["method", "endpoint", "status"]
+    )
+    .unwrap()
+});
+
+/// Number of in-flight requests that were deduplicated by the single-flight
+/// coalescing middleware, i.e. served from a leader's buffered response instead
+/// of executing the handler again. Lets operators observe the coalescing hit
+/// rate.
+pub static COALESCED_REQUESTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aptos_api_coalesced_requests",
+        "Number of requests served from a coalesced single-flight leader response"
+    )
+    .unwrap()
+});
@@ -1,26 +1,397 @@
 // Copyright (c) Aptos
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::BTreeMap;
+use std::sync::Arc;
 use std::time::Duration;
 
-use crate::metrics::RESPONSE_STATUS;
+use crate::metrics::{COALESCED_REQUESTS, RESPONSE_STATUS};
 use aptos_logger::{
     debug, error,
     prelude::{sample, SampleRate},
     sample::Sampling,
-    Schema,
+    warn, Schema,
 };
+use bytes::Bytes;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
 use poem::{
-    http::{header, StatusCode},
-    Endpoint, IntoResponse, Request, Response, Result,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    route::PathPattern,
+    Endpoint, Error, IntoResponse, Request, Response, Result,
 };
+use tokio::sync::broadcast;
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// Header names whose values we never want to see in a log, even when full
+/// header capture is enabled. Compared case-insensitively against the incoming
+/// header names, modeled on the `DebugHeaders` masking approach.
+const DEFAULT_MASKED_HEADERS: [&str; 4] = ["authorization", "cookie", "set-cookie", "x-api-key"];
+
+/// Placeholder substituted for the value of a masked header.
+const MASKED_PLACEHOLDER: &str = "<masked>";
+
+/// Tunables for [`middleware_log`].
+#[derive(Clone, Debug)]
+pub struct HttpLogConfig {
+    /// When true, every request header is recorded in [`HttpRequestLog::headers`],
+    /// with the values of any header in `masked_headers` replaced by
+    /// [`MASKED_PLACEHOLDER`] at capture time.
+    pub capture_headers: bool,
+    /// Header names whose values must never be logged. Matched
+    /// case-insensitively, so entries need not be pre-lowercased.
+    pub masked_headers: Vec<String>,
+    /// Requests below status 500 whose total latency exceeds this threshold
+    /// are logged at `warn` level with `slow: true`, even when they succeed,
+    /// and without the sampling applied to 5xx logs. A slow 5xx response is
+    /// logged only through the sampled `error` log, with `slow: true` set.
+    pub slow_request_threshold: Duration,
+}
+
+impl Default for HttpLogConfig {
+    fn default() -> Self {
+        Self {
+            capture_headers: false,
+            masked_headers: DEFAULT_MASKED_HEADERS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            slow_request_threshold: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Capture all request headers into a map, masking the values of any header
+/// whose (case-insensitive) name is on the deny-list. The masking happens here,
+/// so the sensitive bytes are dropped before the map ever reaches the logger.
+fn capture_headers(headers: &HeaderMap, masked_headers: &[String]) -> BTreeMap<String, String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let name = name.as_str().to_string();
+            let value = if masked_headers
+                .iter()
+                .any(|masked| masked.eq_ignore_ascii_case(&name))
+            {
+                MASKED_PLACEHOLDER.to_string()
+            } else {
+                value.to_str().unwrap_or(MASKED_PLACEHOLDER).to_string()
+            };
+            (name, value)
+        })
+        .collect()
+}
+
+/// A cloneable snapshot of a response, used to hand an identical response to
+/// every waiter coalesced behind a single leader. The body is fully buffered
+/// into [`Bytes`] so it can be replayed any number of times.
+#[derive(Clone)]
+struct ResponseSnapshot {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl ResponseSnapshot {
+    /// Rebuild an identical [`Response`] from the snapshot.
+    fn to_response(&self) -> Response {
+        let mut response = Response::builder().status(self.status).body(self.body.clone());
+        *response.headers_mut() = self.headers.clone();
+        response
+    }
+}
+
+/// The outcome the leader broadcasts to its waiters. `Some` carries a replayable
+/// response — either a cacheable success or a propagated failure — that every
+/// waiter replays verbatim. `None` means there is nothing to replay (a
+/// non-cacheable success, or a body that could not be buffered), in which case
+/// each waiter falls back to executing the handler itself.
+type CoalesceOutcome = Option<ResponseSnapshot>;
+
+/// Tunables for [`middleware_coalesce`].
+#[derive(Clone, Debug)]
+pub struct CoalesceConfig {
+    /// Request headers (lower-cased) that are folded into the fingerprint, so
+    /// that requests differing only in e.g. `accept` are not coalesced together.
+    pub vary_headers: Vec<String>,
+    /// Upper bound a waiter will block on the leader before giving up and
+    /// running the handler itself, so a hung leader cannot stall subscribers.
+    pub leader_timeout: Duration,
+}
+
+impl Default for CoalesceConfig {
+    fn default() -> Self {
+        Self {
+            vary_headers: Vec::new(),
+            leader_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Deduplicates concurrent identical in-flight requests so that a stampede of
+/// identical reads only executes the handler once. The first caller for a
+/// fingerprint becomes the leader and runs the handler; concurrent callers
+/// subscribe and await the leader's buffered response.
+#[derive(Clone, Default)]
+pub struct RequestCoalescer {
+    in_flight: Arc<DashMap<String, broadcast::Sender<CoalesceOutcome>>>,
+    config: CoalesceConfig,
+}
+
+impl RequestCoalescer {
+    pub fn new(config: CoalesceConfig) -> Self {
+        Self {
+            in_flight: Arc::new(DashMap::new()),
+            config,
+        }
+    }
+
+    /// Only idempotent reads are safe to coalesce.
+    fn is_coalescible(method: &Method) -> bool {
+        matches!(*method, Method::GET | Method::HEAD)
+    }
+
+    /// Only successful, cacheable responses are shared with waiters.
+    fn is_cacheable(status: StatusCode) -> bool {
+        status.is_success()
+    }
+
+    /// method + full path + query + any configured vary headers.
+    fn fingerprint(&self, request: &Request) -> String {
+        let mut fingerprint = format!(
+            "{} {}",
+            request.method(),
+            request
+                .uri()
+                .path_and_query()
+                .map(|pq| pq.as_str())
+                .unwrap_or_else(|| request.uri().path())
+        );
+        for name in &self.config.vary_headers {
+            let value = request
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("");
+            fingerprint.push('\n');
+            fingerprint.push_str(name);
+            fingerprint.push(':');
+            fingerprint.push_str(value);
+        }
+        fingerprint
+    }
+}
+
+/// Request coalescing / single-flight middleware for idempotent GET endpoints.
+/// Runs alongside [`middleware_log`]: the first caller for a given fingerprint
+/// executes the handler while concurrent identical callers await its result,
+/// collapsing read stampedes into a single upstream call.
+pub async fn middleware_coalesce<E: Endpoint>(
+    next: E,
+    request: Request,
+    coalescer: &RequestCoalescer,
+) -> Result<Response> {
+    if !RequestCoalescer::is_coalescible(request.method()) {
+        return next.call(request).await.map(IntoResponse::into_response);
+    }
+
+    let key = coalescer.fingerprint(&request);
+
+    // Leader/waiter is decided by the vacancy of the map entry, so that exactly
+    // one caller per fingerprint runs the handler. The entry guard is held only
+    // for this block (no await while it is live, so the shard lock is released
+    // before we ever block on the leader): a vacant slot means we install the
+    // sender and become the leader, while an occupied slot means a leader is
+    // already in flight and we subscribe to its broadcast.
+    let mut receiver = match coalescer.in_flight.entry(key.clone()) {
+        Entry::Vacant(vacant) => {
+            vacant.insert(broadcast::channel(1).0);
+            return run_as_leader(next, request, coalescer, key).await;
+        }
+        Entry::Occupied(occupied) => occupied.get().subscribe(),
+    };
+
+    match tokio::time::timeout(coalescer.config.leader_timeout, receiver.recv()).await {
+        // The leader produced a replayable response (a cacheable success, or a
+        // propagated failure): replay it verbatim. This is the only path on
+        // which the request was genuinely deduplicated, so it is the only one
+        // that counts toward the coalescing hit rate.
+        Ok(Ok(Some(snapshot))) => {
+            COALESCED_REQUESTS.inc();
+            Ok(snapshot.to_response())
+        }
+        // The leader finished but the result was not replayable (a non-cacheable
+        // status), or the channel lagged/closed, or we timed out waiting: fall
+        // back to running the handler ourselves rather than blocking forever.
+        // These callers were not deduplicated, so they are not counted.
+        _ => next.call(request).await.map(IntoResponse::into_response),
+    }
+}
+
+/// Run the handler as the leader, buffer the response so it can be replayed,
+/// broadcast the outcome to any waiters, and always remove the map entry.
+async fn run_as_leader<E: Endpoint>(
+    next: E,
+    request: Request,
+    coalescer: &RequestCoalescer,
+    key: String,
+) -> Result<Response> {
+    let result = next.call(request).await.map(IntoResponse::into_response);
+
+    let (outcome, response): (CoalesceOutcome, Result<Response>) = match result {
+        Ok(response) => {
+            let status = response.status();
+            let headers = response.headers().clone();
+            // Buffer the body once so the leader always gets a valid response
+            // regardless of cacheability, and only hand the snapshot to waiters
+            // when the status is cacheable. A buffering failure means the body
+            // is unrecoverable for everyone, so the leader surfaces that I/O
+            // error and waiters fall back.
+            match response.into_body().into_bytes().await {
+                Ok(body) => {
+                    let snapshot = ResponseSnapshot {
+                        status,
+                        headers,
+                        body,
+                    };
+                    let outcome = if RequestCoalescer::is_cacheable(status) {
+                        Some(snapshot.clone())
+                    } else {
+                        None
+                    };
+                    (outcome, Ok(snapshot.to_response()))
+                }
+                Err(err) => (None, Err(Error::from(err))),
+            }
+        }
+        // Propagate the failure to the waiters currently blocked on us instead
+        // of letting each re-run the handler, which on a persistent failure
+        // would turn single-flight into a thundering herd of N executions. We
+        // materialize the error into a response snapshot and broadcast it; the
+        // leader still returns the original error so its own caller gets normal
+        // error handling. Removing the map entry below means the failure is only
+        // propagated to in-flight waiters, never cached for future callers.
+        Err(err) => {
+            let err_response = err.as_response();
+            let status = err_response.status();
+            let headers = err_response.headers().clone();
+            let outcome = match err_response.into_body().into_bytes().await {
+                Ok(body) => Some(ResponseSnapshot {
+                    status,
+                    headers,
+                    body,
+                }),
+                Err(_) => None,
+            };
+            (outcome, Err(err))
+        }
+    };
+
+    // Hand the outcome to every waiter before we drop the entry. A send error
+    // just means nobody was waiting, which is fine.
+    if let Some((_, sender)) = coalescer.in_flight.remove(&key) {
+        let _ = sender.send(outcome);
+    }
+
+    response
+}
+
+/// Label used on [`RESPONSE_STATUS`] and [`HttpRequestLog::endpoint`] when no
+/// route matched, so that unmatched paths (e.g. 404s) collapse to a single
+/// bounded series instead of one per raw URI.
+const UNKNOWN_ENDPOINT: &str = "unknown";
+
+/// Correlation header read from the incoming request and echoed on the response.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Load-balancer-provided correlation header, used as a fallback source for the
+/// request ID when the client did not set [`REQUEST_ID_HEADER`].
+const AMZN_REQUEST_ID_HEADER: &str = "x-amzn-trace-id";
+
+/// Resolve the correlation ID for this request: prefer a client-supplied
+/// `X-Request-Id`, fall back to a load-balancer trace header, and otherwise mint
+/// a fresh UUID so every request carries exactly one ID end to end.
+fn resolve_request_id(request: &Request) -> String {
+    for name in [REQUEST_ID_HEADER, AMZN_REQUEST_ID_HEADER] {
+        if let Some(value) = request.headers().get(name).and_then(|v| v.to_str().ok()) {
+            if !value.is_empty() {
+                return value.to_string();
+            }
+        }
+    }
+    Uuid::new_v4().to_string()
+}
+
+/// A slot threaded through the request so the matched route template can be
+/// recovered *after* routing has run. Poem only populates [`PathPattern`] while
+/// it routes, which happens inside `next.call`; an app-level middleware that
+/// read the extension before the call — as `middleware_log` does — would always
+/// observe an empty pattern and label every request `"unknown"`. Instead
+/// [`middleware_log`] installs this slot before the call and
+/// [`middleware_record_endpoint`], layered below the router, records the matched
+/// template into it; the shared [`Arc`] lets `middleware_log` read it back once
+/// `next.call` has returned.
+#[derive(Clone, Default)]
+pub struct MatchedEndpoint(Arc<std::sync::OnceLock<String>>);
+
+impl MatchedEndpoint {
+    /// Record the matched route template (e.g. `/accounts/:address/resources`).
+    /// Invoked by [`middleware_record_endpoint`] once a route has matched.
+    pub fn record(&self, pattern: &PathPattern) {
+        let _ = self.0.set(pattern.0.to_string());
+    }
+
+    /// The matched route template, or [`UNKNOWN_ENDPOINT`] when nothing matched,
+    /// so that unmatched paths collapse to a single bounded metrics series.
+    fn resolve(&self) -> String {
+        self.0
+            .get()
+            .cloned()
+            .unwrap_or_else(|| UNKNOWN_ENDPOINT.to_string())
+    }
+}
+
+/// Copies poem's matched [`PathPattern`] into the [`MatchedEndpoint`] slot that
+/// [`middleware_log`] installed, so the templated endpoint can be read back by
+/// `middleware_log` after routing. This must be layered *below* the router,
+/// where poem has already populated `PathPattern`; when no route matched (e.g. a
+/// 404) the slot is left empty and resolves to [`UNKNOWN_ENDPOINT`].
+pub async fn middleware_record_endpoint<E: Endpoint>(
+    next: E,
+    request: Request,
+) -> Result<Response> {
+    if let (Some(slot), Some(pattern)) = (
+        request.extensions().get::<MatchedEndpoint>(),
+        request.extensions().get::<PathPattern>(),
+    ) {
+        slot.record(pattern);
+    }
+    next.call(request).await.map(IntoResponse::into_response)
+}
 
 /// Logs information about the request and response if the response status code
 /// is >= 500, to help us debug since this will be an error on our side.
 /// We also do general logging of the status code alone regardless of what it is.
-pub async fn middleware_log<E: Endpoint>(next: E, request: Request) -> Result<Response> {
+pub async fn middleware_log<E: Endpoint>(
+    next: E,
+    mut request: Request,
+    config: &HttpLogConfig,
+) -> Result<Response> {
     let start = std::time::Instant::now();
 
+    let request_id = resolve_request_id(&request);
+    // Install the slot the routing layer records the matched template into; the
+    // endpoint itself is only known once `next.call` (which routes) returns.
+    let matched = MatchedEndpoint::default();
+    request.extensions_mut().insert(matched.clone());
+
+    let headers = if config.capture_headers {
+        Some(capture_headers(request.headers(), &config.masked_headers))
+    } else {
+        None
+    };
+
     let mut log = HttpRequestLog {
         remote_addr: request.remote_addr().as_socket_addr().cloned(),
         method: request.method().to_string(),
@@ -39,13 +410,41 @@ pub async fn middleware_log<E: Endpoint>(next: E, request: Request) -> Result<Re
             .headers()
             .get(header::FORWARDED)
             .and_then(|v| v.to_str().ok().map(|v| v.to_string())),
+        headers,
+        // Resolved after routing; see below.
+        endpoint: UNKNOWN_ENDPOINT.to_string(),
+        request_id: request_id.clone(),
+        slow: false,
     };
 
-    let result = next.call(request).await;
+    let method = request.method().to_string();
+
+    // Wrap the downstream call in a span carrying the correlation ID and method
+    // so logs emitted during the request can be stitched to a single ID across
+    // its whole lifecycle. The matched endpoint is deliberately omitted: it is
+    // only known after routing runs inside `next.call`, so it cannot be a field
+    // on a span that wraps the call.
+    let span = tracing::info_span!(
+        "http_request",
+        request_id = %request_id,
+        method = %method,
+    );
+    let result = next.call(request).instrument(span).await;
+
+    // Routing has run, so the matched template (if any) is now available.
+    let endpoint = matched.resolve();
+    log.endpoint = endpoint.clone();
 
     let (out, status_code) = match result {
         Ok(response) => {
-            let response = response.into_response();
+            let mut response = response.into_response();
+            // Echo the correlation ID back so clients and load balancers can
+            // stitch their own logs to ours.
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                response
+                    .headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
             let status_code = response.status().as_u16();
             (Ok(response), status_code)
         }
@@ -58,15 +457,25 @@ pub async fn middleware_log<E: Endpoint>(next: E, request: Request) -> Result<Re
 
     log.status = status_code;
     log.elapsed = elapsed;
+    log.slow = elapsed > config.slow_request_threshold;
 
     if status_code >= 500 {
         sample!(SampleRate::Duration(Duration::from_secs(1)), error!(log));
+    } else if log.slow {
+        warn!(log);
     } else {
         debug!(log);
     }
 
+    // These three label values (method, endpoint, status) match the
+    // `RESPONSE_STATUS` HistogramVec definition in `metrics.rs`; the label count
+    // must stay in lockstep or Prometheus panics on the first `observe`.
     RESPONSE_STATUS
-        .with_label_values(&[status_code.to_string().as_str()])
+        .with_label_values(&[
+            method.as_str(),
+            endpoint.as_str(),
+            status_code.to_string().as_str(),
+        ])
         .observe(elapsed.as_secs_f64());
 
     out
@@ -86,4 +495,199 @@ pub struct HttpRequestLog {
     #[schema(debug)]
     pub elapsed: std::time::Duration,
     forwarded: Option<String>,
+    /// The full set of request headers, captured only when
+    /// [`HttpLogConfig::capture_headers`] is enabled, with sensitive values
+    /// already masked.
+    #[schema(debug)]
+    headers: Option<BTreeMap<String, String>>,
+    /// The matched route template (e.g. `/accounts/:address/resources`), or
+    /// `"unknown"` when no route matched. Used as a bounded metrics label.
+    endpoint: String,
+    /// Correlation ID for this request, taken from the incoming `X-Request-Id`
+    /// header, else from `X-Amzn-Trace-Id`, else minted as a fresh UUID. It is
+    /// echoed back on responses from handlers that return `Ok`; when the
+    /// handler returns an error, the error is passed on without the header.
+    request_id: String,
+    /// Whether the request exceeded [`HttpLogConfig::slow_request_threshold`],
+    /// used to flag SLO violations even on successful responses.
+    slow: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn header_map(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn capture_headers_masks_denied_values() {
+        let headers = header_map(&[
+            ("authorization", "Bearer secret-token"),
+            ("cookie", "session=abc"),
+            ("user-agent", "curl/8.0"),
+        ]);
+        let masked = vec!["authorization".to_string(), "cookie".to_string()];
+
+        let captured = capture_headers(&headers, &masked);
+
+        assert_eq!(captured.get("authorization").unwrap(), MASKED_PLACEHOLDER);
+        assert_eq!(captured.get("cookie").unwrap(), MASKED_PLACEHOLDER);
+        assert_eq!(captured.get("user-agent").unwrap(), "curl/8.0");
+    }
+
+    #[test]
+    fn capture_headers_deny_list_is_case_insensitive() {
+        // The header name arrives lower-cased from http, but a mixed-case
+        // deny-list entry must still match.
+        let headers = header_map(&[("authorization", "Bearer secret-token")]);
+        let masked = vec!["Authorization".to_string()];
+
+        let captured = capture_headers(&headers, &masked);
+
+        assert_eq!(captured.get("authorization").unwrap(), MASKED_PLACEHOLDER);
+    }
+
+    #[test]
+    fn capture_headers_defaults_mask_sensitive_headers() {
+        let headers = header_map(&[
+            ("x-api-key", "super-secret"),
+            ("set-cookie", "session=abc"),
+            ("accept", "application/json"),
+        ]);
+        let config = HttpLogConfig::default();
+
+        let captured = capture_headers(&headers, &config.masked_headers);
+
+        assert_eq!(captured.get("x-api-key").unwrap(), MASKED_PLACEHOLDER);
+        assert_eq!(captured.get("set-cookie").unwrap(), MASKED_PLACEHOLDER);
+        assert_eq!(captured.get("accept").unwrap(), "application/json");
+    }
+
+    #[test]
+    fn only_idempotent_cacheable_requests_are_coalesced() {
+        assert!(RequestCoalescer::is_coalescible(&Method::GET));
+        assert!(RequestCoalescer::is_coalescible(&Method::HEAD));
+        assert!(!RequestCoalescer::is_coalescible(&Method::POST));
+
+        assert!(RequestCoalescer::is_cacheable(StatusCode::OK));
+        assert!(!RequestCoalescer::is_cacheable(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!RequestCoalescer::is_cacheable(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn fingerprint_folds_in_path_query_and_vary_headers() {
+        let coalescer = RequestCoalescer::new(CoalesceConfig {
+            vary_headers: vec!["accept".to_string()],
+            ..CoalesceConfig::default()
+        });
+
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("/accounts/0x1/resources?ledger_version=42".parse().unwrap())
+            .header("accept", "application/json")
+            .finish();
+
+        let fingerprint = coalescer.fingerprint(&request);
+
+        assert!(fingerprint.contains("GET"));
+        assert!(fingerprint.contains("/accounts/0x1/resources?ledger_version=42"));
+        assert!(fingerprint.contains("accept:application/json"));
+    }
+
+    /// An endpoint that records how many times it actually executed, sleeping
+    /// briefly so concurrent callers have time to subscribe to the leader.
+    #[derive(Clone)]
+    struct CountingEndpoint {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[poem::async_trait]
+    impl Endpoint for CountingEndpoint {
+        type Output = Response;
+
+        async fn call(&self, _request: Request) -> Result<Self::Output> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(Response::builder().status(StatusCode::OK).body("ok"))
+        }
+    }
+
+    fn get_request() -> Request {
+        Request::builder()
+            .method(Method::GET)
+            .uri("/accounts/0x1/resources".parse().unwrap())
+            .finish()
+    }
+
+    #[tokio::test]
+    async fn concurrent_identical_requests_run_handler_once() {
+        let coalescer = RequestCoalescer::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let endpoint = CountingEndpoint {
+            calls: calls.clone(),
+        };
+
+        // Start the leader first so it installs the in-flight entry before the
+        // waiters look it up.
+        let leader = {
+            let coalescer = coalescer.clone();
+            let endpoint = endpoint.clone();
+            tokio::spawn(async move {
+                middleware_coalesce(endpoint, get_request(), &coalescer).await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let mut waiters = Vec::new();
+        for _ in 0..4 {
+            let coalescer = coalescer.clone();
+            let endpoint = endpoint.clone();
+            waiters.push(tokio::spawn(async move {
+                middleware_coalesce(endpoint, get_request(), &coalescer).await
+            }));
+        }
+
+        let leader_response = leader.await.unwrap().unwrap();
+        assert_eq!(leader_response.status(), StatusCode::OK);
+
+        for waiter in waiters {
+            let response = waiter.await.unwrap().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.into_body().into_string().await.unwrap(), "ok");
+        }
+
+        // Despite five concurrent identical requests, the handler ran once.
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn non_idempotent_requests_are_not_coalesced() {
+        let coalescer = RequestCoalescer::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let endpoint = CountingEndpoint {
+            calls: calls.clone(),
+        };
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/transactions".parse().unwrap())
+            .finish();
+
+        middleware_coalesce(endpoint, request, &coalescer)
+            .await
+            .unwrap();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(coalescer.in_flight.is_empty());
+    }
 }